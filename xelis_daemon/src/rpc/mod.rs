@@ -9,14 +9,16 @@ use actix::{Addr, MailboxError};
 use actix_web::web::Path;
 use actix_web::{get, post, web::{self, Payload}, error::Error, App, HttpResponse, HttpServer, Responder, dev::ServerHandle, ResponseError, HttpRequest};
 use actix_web_actors::ws::WsResponseBuilder;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, Error as SerdeError, json};
-use tokio::sync::Mutex;
+use tokio::{sync::{Mutex, Semaphore, OwnedSemaphorePermit}, task::JoinHandle};
 use xelis_common::api::daemon::{NotifyEvent, EventResult};
 use xelis_common::config;
 use xelis_common::crypto::address::Address;
 use xelis_common::serializer::ReaderError;
 use std::borrow::Cow;
+use std::time::{Duration, Instant};
 use std::{sync::Arc, collections::HashMap, pin::Pin, future::Future, fmt::{Display, Formatter}};
 use log::{trace, info, debug};
 use anyhow::Error as AnyError;
@@ -29,6 +31,8 @@ pub type SharedRpcServer = web::Data<Arc<RpcServer>>;
 pub type Handler = fn(Arc<Blockchain>, Value) -> Pin<Box<dyn Future<Output = Result<Value, RpcError>>>>;
 
 pub const JSON_RPC_VERSION: &str = "2.0";
+// pseudo JSON-RPC method name used on subscription push notifications sent over /ws
+pub const SUBSCRIPTION_NOTIFICATION_METHOD: &str = "subscription";
 
 #[derive(Error, Debug)]
 pub enum RpcError {
@@ -56,8 +60,14 @@ pub enum RpcError {
     NoP2p,
     #[error("WebSocket client is not registered")]
     ClientNotRegistered,
+    #[error("Unknown subscription id")]
+    UnknownSubscription,
     #[error("Could not send message to address: {}", _0)]
     WebSocketSendError(#[from] MailboxError),
+    #[error("{}", _0)]
+    WithData(Box<RpcError>, Value), // wraps another error with context exposed under `error.data`
+    #[error("Too many requests, please slow down")]
+    TooManyRequests,
 }
 
 impl RpcError {
@@ -66,10 +76,25 @@ impl RpcError {
             RpcError::ParseBodyError => -32700,
             RpcError::InvalidRequest | RpcError::InvalidVersion => -32600,
             RpcError::MethodNotFound(_) => -32601,
-            RpcError::InvalidParams(_) | RpcError::UnexpectedParams => -32602,
+            RpcError::InvalidParams(_) | RpcError::UnexpectedParams | RpcError::UnknownSubscription => -32602,
+            RpcError::WithData(error, _) => error.get_code(),
+            RpcError::TooManyRequests => -32000,
             _ => -32603
         }
     }
+
+    // additional machine-readable context to include under the JSON-RPC `error.data` key, if any
+    pub fn data(&self) -> Option<&Value> {
+        match self {
+            RpcError::WithData(_, data) => Some(data),
+            _ => None
+        }
+    }
+
+    // attach structured context to this error, to be exposed under `error.data`
+    pub fn with_data(self, data: Value) -> Self {
+        RpcError::WithData(Box::new(self), data)
+    }
 }
 
 #[derive(Debug)]
@@ -94,13 +119,19 @@ impl RpcResponseError {
     }
 
     pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "code": self.error.get_code(),
+            "message": self.error.to_string()
+        });
+
+        if let Some(data) = self.error.data() {
+            error["data"] = data.clone();
+        }
+
         json!({
             "jsonrpc": JSON_RPC_VERSION,
             "id": self.get_id(),
-            "error": {
-                "code": self.error.get_code(),
-                "message": self.error.to_string()
-            }
+            "error": error
         })
     }
 }
@@ -125,16 +156,170 @@ pub struct RpcRequest {
     params: Option<Value>
 }
 
+// a JSON-RPC 2.0 request body, either a single object or a top-level batch array
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RpcRequestKind {
+    Single(RpcRequest),
+    Batch(Vec<RpcRequest>)
+}
+
+// limits applied per client IP on the `/json_rpc` and `/ws` endpoints
+#[derive(Clone, Copy)]
+pub struct RateLimiterConfig {
+    pub requests_per_second: u32, // size of the token bucket refilled every second
+    pub max_concurrent_requests: usize, // max RPC calls a single IP can have in flight at once
+    pub max_websocket_connections: usize, // max simultaneous `/ws` connections per IP
+    pub trust_proxy_headers: bool // trust client-supplied Forwarded/X-Forwarded-For (only behind a trusted reverse proxy)
+}
+
+// per-IP limiter state: a semaphore caps in-flight requests, a token bucket caps throughput
+struct ClientLimiter {
+    semaphore: Arc<Semaphore>,
+    tokens: f64,
+    last_refill: Instant,
+    websocket_connections: usize
+}
+
+impl ClientLimiter {
+    fn new(config: &RateLimiterConfig) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(config.max_concurrent_requests)),
+            tokens: config.requests_per_second as f64,
+            last_refill: Instant::now(),
+            websocket_connections: 0
+        }
+    }
+}
+
+struct RateLimiter {
+    config: RateLimiterConfig,
+    clients: Mutex<HashMap<String, ClientLimiter>>
+}
+
+impl RateLimiter {
+    fn new(config: RateLimiterConfig) -> Self {
+        Self {
+            config,
+            clients: Mutex::new(HashMap::new())
+        }
+    }
+
+    // refill the token bucket, then consume a token and acquire a concurrency permit
+    async fn acquire(&self, ip: &str) -> Result<OwnedSemaphorePermit, RpcError> {
+        let mut clients = self.clients.lock().await;
+        let client = clients.entry(ip.to_string()).or_insert_with(|| ClientLimiter::new(&self.config));
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(client.last_refill).as_secs_f64();
+        client.last_refill = now;
+        client.tokens = (client.tokens + elapsed * self.config.requests_per_second as f64).min(self.config.requests_per_second as f64);
+
+        if client.tokens < 1.0 {
+            return Err(RpcError::TooManyRequests);
+        }
+
+        let permit = Arc::clone(&client.semaphore).try_acquire_owned().map_err(|_| RpcError::TooManyRequests)?;
+        client.tokens -= 1.0;
+        Ok(permit)
+    }
+
+    // reserve a `/ws` connection slot for this IP, rejecting it if the per-IP cap is reached
+    async fn acquire_websocket_slot(&self, ip: &str) -> Result<(), RpcError> {
+        let mut clients = self.clients.lock().await;
+        let client = clients.entry(ip.to_string()).or_insert_with(|| ClientLimiter::new(&self.config));
+        if client.websocket_connections >= self.config.max_websocket_connections {
+            return Err(RpcError::TooManyRequests);
+        }
+        client.websocket_connections += 1;
+        Ok(())
+    }
+
+    async fn release_websocket_slot(&self, ip: &str) {
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get_mut(ip) {
+            client.websocket_connections = client.websocket_connections.saturating_sub(1);
+        }
+    }
+
+    // drop entries that have been idle (no request, no open websocket) past `idle_after`,
+    // so one-off and IP-rotating callers don't grow this map forever
+    async fn evict_idle(&self, idle_after: Duration) {
+        let mut clients = self.clients.lock().await;
+        let now = Instant::now();
+        clients.retain(|_, client| client.websocket_connections > 0 || now.duration_since(client.last_refill) < idle_after);
+    }
+}
+
+const RATE_LIMITER_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const RATE_LIMITER_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// per-client event subscriptions, keyed by a unique handle so a client can demultiplex them
+#[derive(Default)]
+struct ClientSubscriptions {
+    next_id: u64,
+    subscriptions: HashMap<u64, NotifyEvent>
+}
+
+impl ClientSubscriptions {
+    // returns the existing handle if already subscribed to this event, else allocates one
+    fn subscribe(&mut self, event: NotifyEvent) -> u64 {
+        if let Some(id) = self.subscription_for(&event) {
+            return id;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscriptions.insert(id, event);
+        id
+    }
+
+    fn unsubscribe(&mut self, subscription_id: u64) -> Option<NotifyEvent> {
+        self.subscriptions.remove(&subscription_id)
+    }
+
+    fn subscription_for(&self, event: &NotifyEvent) -> Option<u64> {
+        self.subscriptions.iter().find(|(_, e)| *e == event).map(|(id, _)| *id)
+    }
+}
+
+fn parse_request_body(body: &[u8]) -> Result<RpcRequestKind, RpcResponseError> {
+    let request: RpcRequestKind = serde_json::from_slice(body).map_err(|_| RpcResponseError::new(None, RpcError::ParseBodyError))?;
+    match &request {
+        RpcRequestKind::Single(single) => {
+            if single.jsonrpc != JSON_RPC_VERSION {
+                return Err(RpcResponseError::new(single.id, RpcError::InvalidVersion));
+            }
+        },
+        RpcRequestKind::Batch(batch) => {
+            // an empty batch is explicitly invalid per the JSON-RPC 2.0 spec
+            if batch.is_empty() {
+                return Err(RpcResponseError::new(None, RpcError::InvalidRequest));
+            }
+
+            for request in batch {
+                if request.jsonrpc != JSON_RPC_VERSION {
+                    return Err(RpcResponseError::new(request.id, RpcError::InvalidVersion));
+                }
+            }
+        }
+    };
+    Ok(request)
+}
+
 pub struct RpcServer {
     handle: Mutex<Option<ServerHandle>>, // keep the server handle to stop it gracefully
     methods: HashMap<String, Handler>, // all rpc methods registered
     blockchain: Arc<Blockchain>, // pointer to blockchain data
-    clients: Mutex<HashMap<Addr<WebSocketHandler>, HashMap<NotifyEvent, Option<usize>>>>, // all websocket clients connected with subscriptions linked
-    getwork: Option<SharedGetWorkServer>
+    clients: Mutex<HashMap<Addr<WebSocketHandler>, ClientSubscriptions>>, // all websocket clients connected with subscriptions linked
+    getwork: Option<SharedGetWorkServer>,
+    rate_limiter: Option<RateLimiter>, // per-IP limiting for /json_rpc and /ws, disabled when None
+    trust_proxy_headers: bool, // only trust Forwarded/X-Forwarded-For when behind a configured reverse proxy
+    sweep_task: Mutex<Option<JoinHandle<()>>> // idle rate limiter eviction loop, aborted on stop()
 }
 
 impl RpcServer {
-    pub async fn new(bind_address: String, blockchain: Arc<Blockchain>, disable_getwork_server: bool) -> Result<Arc<Self>, BlockchainError> {
+    pub async fn new(bind_address: String, blockchain: Arc<Blockchain>, disable_getwork_server: bool, rate_limiter_config: Option<RateLimiterConfig>) -> Result<Arc<Self>, BlockchainError> {
         let getwork: Option<SharedGetWorkServer> = if !disable_getwork_server {
             info!("Creating GetWork server...");
             Some(Arc::new(GetWorkServer::new(blockchain.clone())))
@@ -142,11 +327,15 @@ impl RpcServer {
             None
         };
 
+        let trust_proxy_headers = rate_limiter_config.is_some_and(|config| config.trust_proxy_headers);
         let mut server = Self {
             handle: Mutex::new(None),
             methods: HashMap::new(),
             clients: Mutex::new(HashMap::new()),
             getwork,
+            rate_limiter: rate_limiter_config.map(RateLimiter::new),
+            trust_proxy_headers,
+            sweep_task: Mutex::new(None),
             blockchain
         };
         rpc::register_methods(&mut server);
@@ -171,6 +360,19 @@ impl RpcServer {
             *rpc_server.handle.lock().await = Some(handle);
         }
 
+        if rpc_server.rate_limiter.is_some() {
+            let rpc_server_clone = Arc::clone(&rpc_server);
+            let task = tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(RATE_LIMITER_SWEEP_INTERVAL).await;
+                    if let Some(limiter) = &rpc_server_clone.rate_limiter {
+                        limiter.evict_idle(RATE_LIMITER_IDLE_TIMEOUT).await;
+                    }
+                }
+            });
+            *rpc_server.sweep_task.lock().await = Some(task);
+        }
+
         // start the http server
         info!("RPC server will listen on: http://{}", bind_address);
         tokio::spawn(server);
@@ -179,24 +381,26 @@ impl RpcServer {
 
     pub async fn stop(&self) {
         info!("Stopping RPC Server...");
+        if let Some(task) = self.sweep_task.lock().await.take() {
+            task.abort();
+        }
         if let Some(handler) = self.handle.lock().await.take() {
             handler.stop(false).await;
         }
         info!("RPC Server is now stopped!");
     }
 
-    pub fn parse_request(&self, body: &[u8]) -> Result<RpcRequest, RpcResponseError> {
-        let request: RpcRequest = serde_json::from_slice(&body).map_err(|_| RpcResponseError::new(None, RpcError::ParseBodyError))?;
-        if request.jsonrpc != JSON_RPC_VERSION {
-            return Err(RpcResponseError::new(request.id, RpcError::InvalidVersion));
-        }
-        Ok(request)
+    pub fn parse_request(&self, body: &[u8]) -> Result<RpcRequestKind, RpcResponseError> {
+        parse_request_body(body)
     }
 
     pub async fn execute_method(&self, mut request: RpcRequest) -> Result<Value, RpcResponseError> {
         let handler = match self.methods.get(&request.method) {
             Some(handler) => handler,
-            None => return Err(RpcResponseError::new(request.id, RpcError::MethodNotFound(request.method)))
+            None => {
+                let error = RpcError::MethodNotFound(request.method.clone()).with_data(json!({ "method": request.method }));
+                return Err(RpcResponseError::new(request.id, error));
+            }
         };
         trace!("executing '{}' RPC method", request.method);
         let result = handler(Arc::clone(&self.blockchain), request.params.take().unwrap_or(Value::Null)).await.map_err(|err| RpcResponseError::new(request.id, err.into()))?;
@@ -207,6 +411,27 @@ impl RpcServer {
         }))
     }
 
+    // execute every request of a batch concurrently, preserving each request's id,
+    // and drop notification-style requests (no id) from the returned responses.
+    // each contained request is charged against the rate limiter individually, since a
+    // single HTTP call can otherwise smuggle an arbitrarily large batch past the limiter.
+    pub async fn execute_batch(&self, ip: &str, requests: Vec<RpcRequest>) -> Vec<Value> {
+        let futures = requests.into_iter().map(|request| async move {
+            let is_notification = request.id.is_none();
+            let id = request.id;
+            let response = match self.acquire_rate_limit(ip).await {
+                Ok(_permit) => self.execute_method(request).await.unwrap_or_else(|e| e.to_json()),
+                Err(e) => RpcResponseError::new(id, e).to_json()
+            };
+            (is_notification, response)
+        });
+
+        join_all(futures).await
+            .into_iter()
+            .filter_map(|(is_notification, response)| (!is_notification).then_some(response))
+            .collect()
+    }
+
     pub fn register_method(&mut self, name: &str, handler: Handler) {
         if self.methods.insert(name.into(), handler).is_some() {
             error!("The method '{}' was already registered !", name);
@@ -219,26 +444,27 @@ impl RpcServer {
 
     pub async fn add_client(&self, addr: Addr<WebSocketHandler>) {
         let mut clients = self.clients.lock().await;
-        clients.insert(addr, HashMap::new());
+        clients.insert(addr, ClientSubscriptions::default());
     }
 
-    pub async fn remove_client(&self, addr: &Addr<WebSocketHandler>) {
+    pub async fn remove_client(&self, addr: &Addr<WebSocketHandler>, ip: &str) {
         let mut clients = self.clients.lock().await;
         let deleted = clients.remove(addr).is_some();
         debug!("WebSocket client {:?} deleted: {}", addr, deleted);
+        self.release_websocket_slot(ip).await;
     }
 
-    pub async fn subscribe_client_to(&self, addr: &Addr<WebSocketHandler>, subscribe: NotifyEvent, id: Option<usize>) -> Result<(), RpcError> {
+    // returns the subscription handle to send back as the result of the `subscribe` call
+    pub async fn subscribe_client_to(&self, addr: &Addr<WebSocketHandler>, subscribe: NotifyEvent) -> Result<u64, RpcError> {
         let mut clients = self.clients.lock().await;
         let subscriptions = clients.get_mut(addr).ok_or_else(|| RpcError::ClientNotRegistered)?;
-        subscriptions.insert(subscribe, id);
-        Ok(())
+        Ok(subscriptions.subscribe(subscribe))
     }
 
-    pub async fn unsubscribe_client_from(&self, addr: &Addr<WebSocketHandler>, subscribe: &NotifyEvent) -> Result<(), RpcError> {
+    pub async fn unsubscribe_client_from(&self, addr: &Addr<WebSocketHandler>, subscription_id: u64) -> Result<(), RpcError> {
         let mut clients = self.clients.lock().await;
         let subscriptions = clients.get_mut(addr).ok_or_else(|| RpcError::ClientNotRegistered)?;
-        subscriptions.remove(subscribe);
+        subscriptions.unsubscribe(subscription_id).ok_or(RpcError::UnknownSubscription)?;
         Ok(())
     }
 
@@ -247,13 +473,18 @@ impl RpcServer {
     pub async fn notify_clients<V: Serialize>(&self, notify: &NotifyEvent, value: V) -> Result<(), RpcError> {
         let value = json!(EventResult { event: Cow::Borrowed(notify), value: json!(value) });
         let clients = self.clients.lock().await;
-        for (addr, subs) in clients.iter() {
-            if let Some(id) = subs.get(notify) {
+        for (addr, subscriptions) in clients.iter() {
+            if let Some(subscription_id) = subscriptions.subscription_for(notify) {
                 let addr = addr.clone();
+                // sent as a notification (no "id") so it can't be confused with the response
+                // to a client's own in-flight request sharing the same /ws connection
                 let response = Response(json!({
                     "jsonrpc": JSON_RPC_VERSION,
-                    "id": id,
-                    "result": value
+                    "method": SUBSCRIPTION_NOTIFICATION_METHOD,
+                    "params": {
+                        "subscription": subscription_id,
+                        "result": value
+                    }
                 }));
                 tokio::spawn(async move {
                     match addr.send(response).await {
@@ -275,6 +506,39 @@ impl RpcServer {
     pub fn getwork_server(&self) -> &Option<SharedGetWorkServer> {
         &self.getwork
     }
+
+    // caller's IP for rate limiting: trust a forwarding header only when a reverse proxy is
+    // configured, otherwise a client could fake a fresh IP on every request to dodge the limiter
+    pub fn client_ip(&self, request: &HttpRequest) -> String {
+        if self.trust_proxy_headers {
+            request.connection_info().realip_remote_addr().unwrap_or("unknown").to_string()
+        } else {
+            request.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_else(|| "unknown".to_string())
+        }
+    }
+
+    // keep the returned permit alive for the duration of the request it guards; a no-op when disabled
+    pub async fn acquire_rate_limit(&self, ip: &str) -> Result<Option<OwnedSemaphorePermit>, RpcError> {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.acquire(ip).await.map(Some),
+            None => Ok(None)
+        }
+    }
+
+    // reserve a /ws connection slot for this IP, a no-op when rate limiting is disabled
+    pub async fn acquire_websocket_slot(&self, ip: &str) -> Result<(), RpcError> {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.acquire_websocket_slot(ip).await,
+            None => Ok(())
+        }
+    }
+
+    // release a previously-reserved /ws connection slot for this IP
+    pub async fn release_websocket_slot(&self, ip: &str) {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.release_websocket_slot(ip).await;
+        }
+    }
 }
 
 #[get("/")]
@@ -282,17 +546,42 @@ async fn index() -> impl Responder {
     HttpResponse::Ok().body(format!("Hello, world!\nRunning on: {}", config::VERSION))
 }
 
-// TODO support batch
 #[post("/json_rpc")]
-async fn json_rpc(rpc: SharedRpcServer, body: web::Bytes) -> Result<impl Responder, RpcResponseError> {
-    let request = rpc.parse_request(&body)?;
-    let result = rpc.execute_method(request).await?;
-    Ok(HttpResponse::Ok().json(result))
+async fn json_rpc(rpc: SharedRpcServer, request: HttpRequest, body: web::Bytes) -> Result<impl Responder, RpcResponseError> {
+    let ip = rpc.client_ip(&request);
+
+    match rpc.parse_request(&body)? {
+        RpcRequestKind::Single(request) => {
+            let _permit = rpc.acquire_rate_limit(&ip).await.map_err(|e| RpcResponseError::new(None, e))?;
+            let result = rpc.execute_method(request).await?;
+            Ok(HttpResponse::Ok().json(result))
+        },
+        RpcRequestKind::Batch(requests) => {
+            // each request inside the batch is charged individually by execute_batch
+            let responses = rpc.execute_batch(&ip, requests).await;
+            // a batch made of only notifications must produce an empty body
+            if responses.is_empty() {
+                Ok(HttpResponse::Ok().finish())
+            } else {
+                Ok(HttpResponse::Ok().json(responses))
+            }
+        }
+    }
 }
 
 #[get("/ws")]
 async fn ws_endpoint(server: SharedRpcServer, request: HttpRequest, stream: Payload) -> Result<HttpResponse, Error> {
-    let (addr, response) = WsResponseBuilder::new(WebSocketHandler::new(server.clone()), &request, stream).start_with_addr()?;
+    let ip = server.client_ip(&request);
+    server.acquire_websocket_slot(&ip).await.map_err(|e| RpcResponseError::new(None, e))?;
+
+    let (addr, response) = match WsResponseBuilder::new(WebSocketHandler::new(server.clone()), &request, stream).start_with_addr() {
+        Ok(result) => result,
+        Err(e) => {
+            // the slot was reserved above but the actor never started, release it
+            server.release_websocket_slot(&ip).await;
+            return Err(e);
+        }
+    };
     trace!("New client connected to WebSocket: {:?}", addr);
     server.add_client(addr).await;
 
@@ -331,4 +620,88 @@ async fn getwork_endpoint(server: SharedRpcServer, request: HttpRequest, stream:
         },
         None => Ok(HttpResponse::NotFound().reason("GetWork server is not enabled").finish()) // getwork server is not started
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_batch_is_invalid_request() {
+        let err = parse_request_body(b"[]").unwrap_err();
+        assert!(matches!(err.error, RpcError::InvalidRequest));
+    }
+
+    #[test]
+    fn batch_with_notifications_only_parses() {
+        let body = br#"[{"jsonrpc":"2.0","method":"foo"},{"jsonrpc":"2.0","method":"bar"}]"#;
+        match parse_request_body(body).unwrap() {
+            RpcRequestKind::Batch(requests) => assert_eq!(requests.len(), 2),
+            RpcRequestKind::Single(_) => panic!("expected a batch")
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_enforces_concurrency_cap() {
+        let limiter = RateLimiter::new(RateLimiterConfig {
+            requests_per_second: 100,
+            max_concurrent_requests: 1,
+            max_websocket_connections: 1,
+            trust_proxy_headers: false
+        });
+
+        let _first = limiter.acquire("1.2.3.4").await.expect("first permit should be granted");
+        assert!(matches!(limiter.acquire("1.2.3.4").await, Err(RpcError::TooManyRequests)));
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_enforces_requests_per_second() {
+        let limiter = RateLimiter::new(RateLimiterConfig {
+            requests_per_second: 1,
+            max_concurrent_requests: 10,
+            max_websocket_connections: 10,
+            trust_proxy_headers: false
+        });
+
+        drop(limiter.acquire("1.2.3.4").await.expect("first request should be granted"));
+        assert!(matches!(limiter.acquire("1.2.3.4").await, Err(RpcError::TooManyRequests)));
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_evicts_idle_entries() {
+        let limiter = RateLimiter::new(RateLimiterConfig {
+            requests_per_second: 1,
+            max_concurrent_requests: 1,
+            max_websocket_connections: 1,
+            trust_proxy_headers: false
+        });
+
+        drop(limiter.acquire("1.2.3.4").await.expect("permit should be granted"));
+        limiter.evict_idle(Duration::from_secs(0)).await;
+        assert!(limiter.clients.lock().await.is_empty());
+    }
+
+    #[test]
+    fn subscribe_is_idempotent_per_event() {
+        let mut subscriptions = ClientSubscriptions::default();
+        let first = subscriptions.subscribe(NotifyEvent::NewBlock);
+        let second = subscriptions.subscribe(NotifyEvent::NewBlock);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn subscribe_allocates_distinct_handles_per_event() {
+        let mut subscriptions = ClientSubscriptions::default();
+        let a = subscriptions.subscribe(NotifyEvent::NewBlock);
+        let b = subscriptions.subscribe(NotifyEvent::TransactionAddedInMempool);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn unsubscribe_removes_the_handle() {
+        let mut subscriptions = ClientSubscriptions::default();
+        let id = subscriptions.subscribe(NotifyEvent::NewBlock);
+        assert!(subscriptions.unsubscribe(id).is_some());
+        assert!(subscriptions.subscription_for(&NotifyEvent::NewBlock).is_none());
+    }
 }
\ No newline at end of file